@@ -0,0 +1,107 @@
+// Copyright 2021 Ryan Marcus, see COPYING
+use bitpacking::{BitPacker, BitPacker1x, BitPacker4x, BitPacker8x};
+
+/// Bitpacking width a payload was compressed with; `BitPacker4x`/`BitPacker8x` pick their
+/// own SSE3/AVX2-vs-scalar codepath internally, so `new()` never panics on either.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    Scalar,
+    Sse3,
+    Avx2,
+}
+
+impl Backend {
+    pub fn detect() -> Backend {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Backend::Avx2;
+            }
+            if is_x86_feature_detected!("sse3") {
+                return Backend::Sse3;
+            }
+        }
+
+        return Backend::Scalar;
+    }
+
+    pub fn from_tag(tag: u8) -> Backend {
+        return match tag {
+            0 => Backend::Scalar,
+            1 => Backend::Sse3,
+            2 => Backend::Avx2,
+            _ => panic!("unknown bitpacking backend tag: {}", tag),
+        };
+    }
+
+    pub fn tag(self) -> u8 {
+        return match self {
+            Backend::Scalar => 0,
+            Backend::Sse3 => 1,
+            Backend::Avx2 => 2,
+        };
+    }
+
+    pub fn block_len(self) -> usize {
+        return match self {
+            Backend::Scalar => BitPacker1x::BLOCK_LEN,
+            Backend::Sse3 => BitPacker4x::BLOCK_LEN,
+            Backend::Avx2 => BitPacker8x::BLOCK_LEN,
+        };
+    }
+
+    pub fn num_bits(self, data: &[u32]) -> u8 {
+        return match self {
+            Backend::Scalar => BitPacker1x::new().num_bits(data),
+            Backend::Sse3 => BitPacker4x::new().num_bits(data),
+            Backend::Avx2 => BitPacker8x::new().num_bits(data),
+        };
+    }
+
+    pub fn compress(self, data: &[u32], out: &mut [u8], num_bits: u8) -> usize {
+        return match self {
+            Backend::Scalar => BitPacker1x::new().compress(data, out, num_bits),
+            Backend::Sse3 => BitPacker4x::new().compress(data, out, num_bits),
+            Backend::Avx2 => BitPacker8x::new().compress(data, out, num_bits),
+        };
+    }
+
+    pub fn decompress(self, data: &[u8], out: &mut [u32], num_bits: u8) -> usize {
+        return match self {
+            Backend::Scalar => BitPacker1x::new().decompress(data, out, num_bits),
+            Backend::Sse3 => BitPacker4x::new().decompress(data, out, num_bits),
+            Backend::Avx2 => BitPacker8x::new().decompress(data, out, num_bits),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_round_trip() {
+        for backend in [Backend::Scalar, Backend::Sse3, Backend::Avx2] {
+            assert_eq!(Backend::from_tag(backend.tag()), backend);
+        }
+    }
+
+    #[test]
+    fn test_every_backend_round_trips() {
+        for backend in [Backend::Scalar, Backend::Sse3, Backend::Avx2] {
+            let block_len = backend.block_len();
+            let data: Vec<u32> = (0..block_len as u32).map(|i| i % 17).collect();
+            let num_bits = backend.num_bits(&data);
+
+            let mut compressed = vec![0_u8; block_len * 4];
+            let bytes_written = backend.compress(&data, &mut compressed, num_bits);
+
+            let mut recovered = vec![0_u32; block_len];
+            let bytes_read =
+                backend.decompress(&compressed[..bytes_written], &mut recovered, num_bits);
+
+            assert_eq!(bytes_read, bytes_written);
+            assert_eq!(recovered, data);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2021 Ryan Marcus, see COPYING
+
+/// Number of Lehmer-code symbols folded into one mixed-radix "digit group". Encoding a
+/// whole permutation as a single factorial-base integer would need O(n) big-integer limbs
+/// and O(n) time per multiply, making the whole pass O(n^2); fixed-size groups bound that
+/// cost while still getting most of the way to `ceil(log2(n!))` bits.
+pub const GROUP_SIZE: usize = 128;
+
+// Multiplies the base-256, little-endian big integer `digits` by `factor` and adds
+// `addend`, carrying between bytes. `digits` never holds a zero-valued leading byte
+// after this call returns, so its length is always the minimal byte count for the result.
+fn mul_add(digits: &mut Vec<u8>, factor: u32, addend: u32) {
+    let mut carry = addend as u64;
+    for byte in digits.iter_mut() {
+        carry += *byte as u64 * factor as u64;
+        *byte = carry as u8;
+        carry >>= 8;
+    }
+    while carry > 0 {
+        digits.push(carry as u8);
+        carry >>= 8;
+    }
+}
+
+// Divides the base-256, little-endian big integer `digits` by `divisor` in place (most
+// significant byte first) and returns the remainder.
+fn div_mod(digits: &mut [u8], divisor: u32) -> u32 {
+    let mut rem: u64 = 0;
+    for byte in digits.iter_mut().rev() {
+        let cur = (rem << 8) | *byte as u64;
+        *byte = (cur / divisor as u64) as u8;
+        rem = cur % divisor as u64;
+    }
+
+    return rem as u32;
+}
+
+// Reads the low 4 bytes of a base-256, little-endian big integer as a `u32`. Only called
+// once `digits` has been divided down to a value known to fit (a Lehmer symbol), at which
+// point any bytes past the first four are leftover zeroes from the original group value.
+fn digits_to_u32(digits: &[u8]) -> u32 {
+    let mut buf = [0_u8; 4];
+    let n = usize::min(digits.len(), 4);
+    buf[..n].copy_from_slice(&digits[..n]);
+    debug_assert!(digits[n..].iter().all(|&byte| byte == 0));
+
+    return u32::from_le_bytes(buf);
+}
+
+// Lehmer code element `group_start + j` is drawn from `[0, perm_len - 1 - (group_start + j)]`,
+// i.e. it has radix `perm_len - (group_start + j)`.
+fn radix_at(perm_len: usize, index: usize) -> u32 {
+    return (perm_len - index) as u32;
+}
+
+/// Packs one group of Lehmer-code symbols into the minimal number of base-256 bytes for
+/// the mixed-radix integer `v = (((L[0]*r[1] + L[1])*r[2] + L[2]) ... )`, where `r[j]` is
+/// the radix of `lehmer[j]`. `group_start` is the index of `lehmer[0]` within the full
+/// permutation, which is what determines each symbol's radix.
+pub fn encode_group(lehmer: &[u32], perm_len: usize, group_start: usize) -> Vec<u8> {
+    let mut digits = Vec::new();
+    mul_add(&mut digits, 1, lehmer[0]);
+
+    for (j, &symbol) in lehmer.iter().enumerate().skip(1) {
+        let radix = radix_at(perm_len, group_start + j);
+        mul_add(&mut digits, radix, symbol);
+    }
+
+    return digits;
+}
+
+/// Inverts `encode_group`: recovers the `group_len` Lehmer-code symbols that started at
+/// `group_start` in a permutation of length `perm_len` from their packed bytes.
+pub fn decode_group(bytes: &[u8], perm_len: usize, group_start: usize, group_len: usize) -> Vec<u32> {
+    let mut digits = bytes.to_vec();
+    let mut lehmer = vec![0_u32; group_len];
+
+    for j in (1..group_len).rev() {
+        let radix = radix_at(perm_len, group_start + j);
+        lehmer[j] = div_mod(&mut digits, radix);
+    }
+    lehmer[0] = digits_to_u32(&digits);
+
+    return lehmer;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mul_add_div_mod_round_trip() {
+        let mut digits = Vec::new();
+        let radixes = [7_u32, 900, 3, 65536, 2];
+        let symbols = [5_u32, 432, 1, 12345, 0];
+
+        mul_add(&mut digits, 1, symbols[0]);
+        for i in 1..radixes.len() {
+            mul_add(&mut digits, radixes[i], symbols[i]);
+        }
+
+        for i in (1..radixes.len()).rev() {
+            assert_eq!(div_mod(&mut digits, radixes[i]), symbols[i]);
+        }
+        assert_eq!(digits_to_u32(&digits), symbols[0]);
+    }
+
+    #[test]
+    fn test_encode_decode_group_round_trip() {
+        let perm_len = 10_000;
+        let group_start = 37;
+        let lehmer: Vec<u32> = (0..GROUP_SIZE as u32)
+            .map(|j| (j * 31 + 7) % radix_at(perm_len, group_start + j as usize))
+            .collect();
+
+        let encoded = encode_group(&lehmer, perm_len, group_start);
+        let decoded = decode_group(&encoded, perm_len, group_start, lehmer.len());
+
+        assert_eq!(decoded, lehmer);
+    }
+
+    #[test]
+    fn test_encode_decode_partial_group() {
+        let perm_len = 50;
+        let group_start = 40;
+        let lehmer: Vec<u32> = (0..(perm_len - group_start) as u32)
+            .map(|j| j % radix_at(perm_len, group_start + j as usize))
+            .collect();
+
+        let encoded = encode_group(&lehmer, perm_len, group_start);
+        let decoded = decode_group(&encoded, perm_len, group_start, lehmer.len());
+
+        assert_eq!(decoded, lehmer);
+    }
+
+    #[test]
+    fn test_encode_is_near_optimal_size() {
+        // a group near the end of the permutation has small radixes, so it should pack
+        // into far fewer than 4 bytes per symbol (the naive per-symbol-u32 size, 40 bytes
+        // for these 10 symbols).
+        let perm_len = 1000;
+        let group_start = 990;
+        let lehmer = vec![0_u32; perm_len - group_start];
+
+        let encoded = encode_group(&lehmer, perm_len, group_start);
+        assert!(encoded.len() <= 4);
+    }
+}
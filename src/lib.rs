@@ -1,17 +1,27 @@
 // Copyright 2021 Ryan Marcus, see COPYING
 #![allow(clippy::needless_return)]
 
+mod backend;
+mod factorial;
 mod lr_array;
+mod secondary;
 
+use std::io::{self, Read, Write};
 use std::{convert::TryInto, ops::Range};
 
-use bitpacking::{BitPacker, BitPacker4x};
+use backend::Backend;
 use lr_array::LRArray;
+pub use secondary::SecondaryCodec;
+use secondary::{SecondaryReader, SecondaryWriter};
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum CompressionMode {
     Fast,
     Slow,
+    /// Encodes the Lehmer code in the factorial number system instead of bitpacking it,
+    /// reaching close to the information-theoretic minimum of `ceil(log2(n!))` bits at the
+    /// cost of slower, non-seekable encode/decode. See the `factorial` module.
+    Optimal,
 }
 
 fn perm_to_lehmer(perm: &mut [u32]) {
@@ -30,59 +40,130 @@ fn lehmer_to_perm(lehmer: &mut [u32]) {
     }
 }
 
-pub fn compress_permutation(cmode: CompressionMode, mut perm: Vec<u32>) -> Vec<u8> {
+// Shared by `compress_permutation` and `CompressedPermutation::compress`: bitpacks `perm`
+// block-by-block and also returns the byte offset of each block's `num_bits` header, so
+// callers that want seekable range access don't have to re-derive them later. The chosen
+// `Backend` is written as a one-byte tag right after the length prefix, so a payload
+// compressed with SIMD on one machine can still be decoded on another.
+fn compress_permutation_blocks(cmode: CompressionMode, mut perm: Vec<u32>) -> (Vec<u8>, Vec<u32>) {
     if cmode == CompressionMode::Slow {
         perm_to_lehmer(&mut perm);
     }
 
-    let packer = BitPacker4x::new();
-    let perm_len = usize::max(perm.len(), BitPacker4x::BLOCK_LEN);
-    let mut compressed = vec![0_u8; 4 + (perm_len * 4) + (perm_len / BitPacker4x::BLOCK_LEN)];
+    let backend = Backend::detect();
+    let block_len = backend.block_len();
+    let perm_len = usize::max(perm.len(), block_len);
+    let mut compressed = vec![0_u8; 5 + (perm_len * 4) + (perm_len / block_len)];
+    let mut block_offsets = Vec::with_capacity(perm_len / block_len + 1);
 
     compressed[0..4].copy_from_slice(&(perm.len() as u32).to_le_bytes());
+    compressed[4] = backend.tag();
 
-    let mut next_free_index = 4;
+    let mut next_free_index = 5;
+
+    for idx in (0..perm.len()).step_by(block_len) {
+        block_offsets.push(next_free_index as u32);
 
-    for idx in (0..perm.len()).step_by(BitPacker4x::BLOCK_LEN) {
         let start = idx;
-        let stop = usize::min(perm.len(), idx + BitPacker4x::BLOCK_LEN);
+        let stop = usize::min(perm.len(), idx + block_len);
         let data = &perm[start..stop];
 
-        let bytes_written = if data.len() == BitPacker4x::BLOCK_LEN {
-            let num_bits = packer.num_bits(data);
+        let bytes_written = if data.len() == block_len {
+            let num_bits = backend.num_bits(data);
             compressed[next_free_index] = num_bits;
             next_free_index += 1;
 
-            packer.compress(data, &mut compressed[next_free_index..], num_bits)
+            backend.compress(data, &mut compressed[next_free_index..], num_bits)
         } else {
-            let mut padded = vec![0_u32; BitPacker4x::BLOCK_LEN];
+            let mut padded = vec![0_u32; block_len];
             padded[0..data.len()].copy_from_slice(data);
 
-            let num_bits = packer.num_bits(&padded);
+            let num_bits = backend.num_bits(&padded);
             compressed[next_free_index] = num_bits;
             next_free_index += 1;
 
-            packer.compress(&padded, &mut compressed[next_free_index..], num_bits)
+            backend.compress(&padded, &mut compressed[next_free_index..], num_bits)
         };
         next_free_index += bytes_written;
     }
 
     compressed.truncate(next_free_index);
+    return (compressed, block_offsets);
+}
+
+// Encodes `perm`'s Lehmer code in the factorial number system, `factorial::GROUP_SIZE`
+// symbols at a time: `[perm_len][group 0 byte len][group 0 bytes][group 1 byte len]...`.
+fn compress_permutation_optimal(mut perm: Vec<u32>) -> Vec<u8> {
+    perm_to_lehmer(&mut perm);
+    let perm_len = perm.len();
+
+    let mut compressed = Vec::new();
+    compressed.extend_from_slice(&(perm_len as u32).to_le_bytes());
+
+    for group_start in (0..perm_len).step_by(factorial::GROUP_SIZE) {
+        let group_stop = usize::min(perm_len, group_start + factorial::GROUP_SIZE);
+        let digits = factorial::encode_group(&perm[group_start..group_stop], perm_len, group_start);
+
+        compressed.extend_from_slice(&(digits.len() as u32).to_le_bytes());
+        compressed.extend_from_slice(&digits);
+    }
+
     return compressed;
 }
 
-pub fn decompress_permutation(cmode: CompressionMode, data: &[u8]) -> Vec<u32> {
-    let packer = BitPacker4x::new();
+fn decompress_permutation_optimal(data: &[u8]) -> Vec<u32> {
     let perm_len = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
     let mut next_byte = 4;
+    let mut lehmer = Vec::with_capacity(perm_len);
+
+    for group_start in (0..perm_len).step_by(factorial::GROUP_SIZE) {
+        let group_len = usize::min(factorial::GROUP_SIZE, perm_len - group_start);
+
+        let digit_len =
+            u32::from_le_bytes(data[next_byte..next_byte + 4].try_into().unwrap()) as usize;
+        next_byte += 4;
+
+        let digits = &data[next_byte..next_byte + digit_len];
+        next_byte += digit_len;
+
+        lehmer.extend(factorial::decode_group(digits, perm_len, group_start, group_len));
+    }
+
+    lehmer_to_perm(&mut lehmer);
+    return lehmer;
+}
+
+// Shared by `compress_permutation` and `CompressedPermutation::compress`: `Optimal` mode
+// has no fixed-width blocks to index, so it reports an empty block-offset list.
+fn compress_permutation_data(cmode: CompressionMode, perm: Vec<u32>) -> (Vec<u8>, Vec<u32>) {
+    if cmode == CompressionMode::Optimal {
+        return (compress_permutation_optimal(perm), Vec::new());
+    }
+
+    return compress_permutation_blocks(cmode, perm);
+}
+
+pub fn compress_permutation(cmode: CompressionMode, perm: Vec<u32>) -> Vec<u8> {
+    return compress_permutation_data(cmode, perm).0;
+}
+
+pub fn decompress_permutation(cmode: CompressionMode, data: &[u8]) -> Vec<u32> {
+    if cmode == CompressionMode::Optimal {
+        return decompress_permutation_optimal(data);
+    }
+
+    let perm_len = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+    let backend = Backend::from_tag(data[4]);
+    let block_len = backend.block_len();
+    let mut next_byte = 5;
     let mut result = Vec::with_capacity(perm_len);
 
-    let mut block = vec![0; BitPacker4x::BLOCK_LEN];
+    let mut block = vec![0; block_len];
     while next_byte != data.len() {
         let num_bits = data[next_byte];
         next_byte += 1;
 
-        next_byte += packer.decompress(&data[next_byte..], &mut block, num_bits);
+        next_byte += backend.decompress(&data[next_byte..], &mut block, num_bits);
 
         result.extend_from_slice(&block);
     }
@@ -94,51 +175,55 @@ pub fn decompress_permutation(cmode: CompressionMode, data: &[u8]) -> Vec<u32> {
     return result;
 }
 
+// Shared by `decompress_permutation_range` and `CompressedPermutation::decompress_range`:
+// a decoded block of `block_len` elements starting at `block_idx` overlaps `range` over
+// `block[overlap.start..overlap.end]`. Callers only invoke this for blocks already known
+// to intersect `range`, so `range.end >= block_idx * block_len` always holds.
+fn block_range_overlap(range: &Range<usize>, block_len: usize, block_idx: usize) -> Range<usize> {
+    let block_start = block_idx * block_len;
+    let block_stop = block_start + block_len;
+
+    let rel_start = range.start.saturating_sub(block_start);
+    let rel_end = if range.end < block_stop {
+        range.end - block_start
+    } else {
+        block_len
+    };
+
+    return rel_start..rel_end;
+}
+
 pub fn decompress_permutation_range(
     cmode: CompressionMode,
     data: &[u8],
     range: Range<usize>,
 ) -> Vec<u32> {
-    if cmode == CompressionMode::Slow {
+    if cmode == CompressionMode::Slow || cmode == CompressionMode::Optimal {
         let perm = decompress_permutation(cmode, data);
         return perm[range].to_vec();
     }
 
-    let packer = BitPacker4x::new();
-    let perm_len = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
-    let mut next_byte = 4;
+    let backend = Backend::from_tag(data[4]);
+    let block_len = backend.block_len();
+    let mut next_byte = 5;
     let mut result = Vec::with_capacity(range.len());
 
-    let mut block = vec![0; BitPacker4x::BLOCK_LEN];
+    let mut block = vec![0; block_len];
 
     // inclusive bounds
-    let first_block_idx = range.start / BitPacker4x::BLOCK_LEN;
-    let last_block_idx = range.end / BitPacker4x::BLOCK_LEN;
+    let first_block_idx = range.start / block_len;
+    let last_block_idx = range.end.saturating_sub(1) / block_len;
 
     let mut curr_block_idx = 0;
     while next_byte != data.len() {
         let num_bits = data[next_byte];
         next_byte += 1;
 
-        next_byte += packer.decompress(&data[next_byte..], &mut block, num_bits);
+        next_byte += backend.decompress(&data[next_byte..], &mut block, num_bits);
 
         if curr_block_idx >= first_block_idx && curr_block_idx <= last_block_idx {
-            let curr_block_start = curr_block_idx * BitPacker4x::BLOCK_LEN;
-            let curr_block_stop = curr_block_start + BitPacker4x::BLOCK_LEN;
-
-            let rel_start = if range.start > curr_block_start {
-                range.start - curr_block_start
-            } else {
-                0
-            };
-
-            let rel_end = if range.end < curr_block_stop {
-                range.end - curr_block_start
-            } else {
-                BitPacker4x::BLOCK_LEN
-            };
-
-            result.extend_from_slice(&block[rel_start..rel_end]);
+            let overlap = block_range_overlap(&range, block_len, curr_block_idx);
+            result.extend_from_slice(&block[overlap]);
         }
 
         curr_block_idx += 1;
@@ -147,6 +232,223 @@ pub fn decompress_permutation_range(
     return result;
 }
 
+/// Streaming counterpart to `compress_permutation`: writes each bitpacked block as soon as
+/// it's produced instead of buffering the whole payload. `CompressionMode::Optimal` has no
+/// fixed-width blocks and is not supported here.
+pub fn compress_permutation_to<W: Write>(
+    cmode: CompressionMode,
+    secondary: SecondaryCodec,
+    mut perm: Vec<u32>,
+    writer: &mut W,
+) -> io::Result<()> {
+    assert_ne!(
+        cmode,
+        CompressionMode::Optimal,
+        "streaming is not supported for CompressionMode::Optimal"
+    );
+
+    if cmode == CompressionMode::Slow {
+        perm_to_lehmer(&mut perm);
+    }
+
+    let backend = Backend::detect();
+    let block_len = backend.block_len();
+
+    writer.write_all(&(perm.len() as u32).to_le_bytes())?;
+    writer.write_all(&[backend.tag()])?;
+    writer.write_all(&[secondary.tag()])?;
+
+    let mut sink = SecondaryWriter::new(secondary, writer);
+    let mut block_bytes = vec![0_u8; 1 + block_len * 4];
+
+    for idx in (0..perm.len()).step_by(block_len) {
+        let start = idx;
+        let stop = usize::min(perm.len(), idx + block_len);
+        let data = &perm[start..stop];
+
+        let written = if data.len() == block_len {
+            let num_bits = backend.num_bits(data);
+            block_bytes[0] = num_bits;
+            1 + backend.compress(data, &mut block_bytes[1..], num_bits)
+        } else {
+            let mut padded = vec![0_u32; block_len];
+            padded[0..data.len()].copy_from_slice(data);
+
+            let num_bits = backend.num_bits(&padded);
+            block_bytes[0] = num_bits;
+            1 + backend.compress(&padded, &mut block_bytes[1..], num_bits)
+        };
+
+        sink.write_all(&block_bytes[..written])?;
+    }
+
+    sink.finish()?;
+    return Ok(());
+}
+
+// Reads one block's `num_bits` header and packed bytes from `source` and decodes it into
+// `block`; `decompress_permutation_from` reuses the same `packed` and `block` buffers for
+// every block.
+fn read_decompressed_block<R: Read>(
+    source: &mut SecondaryReader<R>,
+    backend: Backend,
+    block_len: usize,
+    packed: &mut [u8],
+    block: &mut [u32],
+) -> io::Result<()> {
+    let mut num_bits = [0_u8; 1];
+    source.read_exact(&mut num_bits)?;
+    let num_bits = num_bits[0];
+
+    let bytes_needed = num_bits as usize * block_len / 8;
+    source.read_exact(&mut packed[..bytes_needed])?;
+    backend.decompress(&packed[..bytes_needed], block, num_bits);
+
+    return Ok(());
+}
+
+/// Streaming counterpart to `decompress_permutation`: decodes one block at a time and
+/// hands each decoded chunk to `on_block`, rather than returning the whole permutation.
+/// `CompressionMode::Fast` calls `on_block` once per block and never holds more than one
+/// block in memory; `CompressionMode::Slow` needs the whole Lehmer code before it can be
+/// inverted, so it buffers internally and calls `on_block` once with the final result.
+/// `CompressionMode::Optimal` has no fixed-width blocks and is not supported here.
+pub fn decompress_permutation_from<R: Read>(
+    cmode: CompressionMode,
+    reader: &mut R,
+    mut on_block: impl FnMut(&[u32]) -> io::Result<()>,
+) -> io::Result<()> {
+    assert_ne!(
+        cmode,
+        CompressionMode::Optimal,
+        "streaming is not supported for CompressionMode::Optimal"
+    );
+
+    let mut header = [0_u8; 6];
+    reader.read_exact(&mut header)?;
+
+    let perm_len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+    let backend = Backend::from_tag(header[4]);
+    let secondary = SecondaryCodec::from_tag(header[5]);
+    let block_len = backend.block_len();
+
+    let mut source = SecondaryReader::new(secondary, reader);
+    let mut packed = vec![0_u8; block_len * 4];
+    let mut block = vec![0_u32; block_len];
+    let num_blocks = perm_len.div_ceil(block_len);
+
+    if cmode == CompressionMode::Fast {
+        let mut emitted = 0;
+        for _ in 0..num_blocks {
+            read_decompressed_block(&mut source, backend, block_len, &mut packed, &mut block)?;
+
+            let take = usize::min(block_len, perm_len - emitted);
+            on_block(&block[..take])?;
+            emitted += take;
+        }
+        return Ok(());
+    }
+
+    let mut lehmer = Vec::with_capacity(perm_len);
+    for _ in 0..num_blocks {
+        read_decompressed_block(&mut source, backend, block_len, &mut packed, &mut block)?;
+        lehmer.extend_from_slice(&block);
+    }
+
+    lehmer.truncate(perm_len);
+    lehmer_to_perm(&mut lehmer);
+    on_block(&lehmer)?;
+    return Ok(());
+}
+
+/// A compressed permutation paired with a parsed block index, so that repeated calls to
+/// `decompress_range` can seek directly to the relevant blocks instead of re-scanning the
+/// whole payload. The index is serialized as a trailer after the bitpacked blocks, so
+/// `as_bytes()` / `from_bytes()` round-trip the whole thing (payload and index together).
+pub struct CompressedPermutation {
+    data: Vec<u8>,
+    block_offsets: Vec<u32>,
+}
+
+impl CompressedPermutation {
+    pub fn compress(cmode: CompressionMode, perm: Vec<u32>) -> CompressedPermutation {
+        let (mut data, block_offsets) = compress_permutation_data(cmode, perm);
+
+        for offset in &block_offsets {
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        data.extend_from_slice(&(block_offsets.len() as u32).to_le_bytes());
+
+        return CompressedPermutation { data, block_offsets };
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> CompressedPermutation {
+        let num_blocks =
+            u32::from_le_bytes(data[data.len() - 4..].try_into().unwrap()) as usize;
+        let trailer_start = data.len() - 4 - num_blocks * 4;
+
+        let block_offsets = data[trailer_start..data.len() - 4]
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+
+        return CompressedPermutation { data, block_offsets };
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        return &self.data;
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        return self.data;
+    }
+
+    fn payload(&self) -> &[u8] {
+        let trailer_len = 4 + self.block_offsets.len() * 4;
+        return &self.data[..self.data.len() - trailer_len];
+    }
+
+    pub fn decompress(&self, cmode: CompressionMode) -> Vec<u32> {
+        return decompress_permutation(cmode, self.payload());
+    }
+
+    pub fn decompress_range(&self, cmode: CompressionMode, range: Range<usize>) -> Vec<u32> {
+        if cmode == CompressionMode::Slow || cmode == CompressionMode::Optimal {
+            let perm = self.decompress(cmode);
+            return perm[range].to_vec();
+        }
+
+        if self.block_offsets.is_empty() {
+            return Vec::new();
+        }
+
+        let payload = self.payload();
+        let backend = Backend::from_tag(payload[4]);
+        let block_len = backend.block_len();
+        let mut result = Vec::with_capacity(range.len());
+        let mut block = vec![0; block_len];
+
+        let first_block_idx = range.start / block_len;
+        let last_block_idx = usize::min(
+            range.end.saturating_sub(1) / block_len,
+            self.block_offsets.len() - 1,
+        );
+
+        for curr_block_idx in first_block_idx..=last_block_idx {
+            let mut next_byte = self.block_offsets[curr_block_idx] as usize;
+            let num_bits = payload[next_byte];
+            next_byte += 1;
+
+            backend.decompress(&payload[next_byte..], &mut block, num_bits);
+
+            let overlap = block_range_overlap(&range, block_len, curr_block_idx);
+            result.extend_from_slice(&block[overlap]);
+        }
+
+        return result;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use itertools::Itertools;
@@ -226,6 +528,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_compress_random_perm_optimal() {
+        for _ in 0..1000 {
+            let mut perm = random_lehmer(20);
+            lehmer_to_perm(&mut perm);
+            let orig = perm.clone();
+
+            let compressed = compress_permutation(CompressionMode::Optimal, perm);
+            let recovered = decompress_permutation(CompressionMode::Optimal, &compressed);
+
+            assert_eq!(recovered, orig);
+        }
+    }
+
+    #[test]
+    fn test_compress_random_perm_optimal_large() {
+        let mut perm = random_lehmer(500);
+        lehmer_to_perm(&mut perm);
+        let orig = perm.clone();
+
+        let compressed = compress_permutation(CompressionMode::Optimal, perm);
+        let recovered = decompress_permutation(CompressionMode::Optimal, &compressed);
+
+        assert_eq!(recovered, orig);
+    }
+
     #[test]
     fn test_compress_random_perm_fast_subset() {
         let mut perm = random_lehmer(500);
@@ -246,4 +574,148 @@ mod tests {
 
         assert_eq!(recovered, orig);
     }
+
+    #[test]
+    fn test_decompress_range_exact_block_boundary() {
+        let mut perm = random_lehmer(500);
+        lehmer_to_perm(&mut perm);
+        let orig = perm.clone();
+
+        let block_len = Backend::detect().block_len();
+        let compressed = compress_permutation(CompressionMode::Fast, perm.clone());
+        let slc = decompress_permutation_range(CompressionMode::Fast, &compressed, 0..block_len);
+        assert_eq!(slc, &orig[0..block_len]);
+
+        let indexed = CompressedPermutation::compress(CompressionMode::Fast, perm);
+        let slc = indexed.decompress_range(CompressionMode::Fast, 0..block_len);
+        assert_eq!(slc, &orig[0..block_len]);
+    }
+
+    #[test]
+    fn test_indexed_compress_random_perm() {
+        for cmode in [CompressionMode::Fast, CompressionMode::Slow, CompressionMode::Optimal] {
+            let mut perm = random_lehmer(500);
+            lehmer_to_perm(&mut perm);
+            let orig = perm.clone();
+
+            let compressed = CompressedPermutation::compress(cmode, perm);
+            assert_eq!(compressed.decompress(cmode), orig);
+        }
+    }
+
+    #[test]
+    fn test_indexed_compress_empty_perm() {
+        for cmode in [CompressionMode::Fast, CompressionMode::Slow, CompressionMode::Optimal] {
+            let compressed = CompressedPermutation::compress(cmode, Vec::new());
+            assert_eq!(compressed.decompress(cmode), Vec::<u32>::new());
+            assert_eq!(compressed.decompress_range(cmode, 0..0), Vec::<u32>::new());
+        }
+    }
+
+    #[test]
+    fn test_indexed_compress_random_perm_subset() {
+        let mut perm = random_lehmer(500);
+        lehmer_to_perm(&mut perm);
+        let orig = perm.clone();
+
+        let compressed = CompressedPermutation::compress(CompressionMode::Fast, perm);
+        let recovered = compressed.decompress(CompressionMode::Fast);
+
+        let slc = compressed.decompress_range(CompressionMode::Fast, 0..10);
+        assert_eq!(slc, &recovered[0..10]);
+
+        let slc = compressed.decompress_range(CompressionMode::Fast, 100..200);
+        assert_eq!(slc, &recovered[100..200]);
+
+        let slc = compressed.decompress_range(CompressionMode::Fast, 100..490);
+        assert_eq!(slc, &recovered[100..490]);
+
+        assert_eq!(recovered, orig);
+    }
+
+    #[test]
+    fn test_indexed_compress_round_trips_bytes() {
+        let mut perm = random_lehmer(500);
+        lehmer_to_perm(&mut perm);
+        let orig = perm.clone();
+
+        let compressed = CompressedPermutation::compress(CompressionMode::Fast, perm);
+        let bytes = compressed.as_bytes().to_vec();
+        let reloaded = CompressedPermutation::from_bytes(bytes);
+
+        assert_eq!(reloaded.decompress(CompressionMode::Fast), orig);
+        assert_eq!(
+            reloaded.decompress_range(CompressionMode::Fast, 100..200),
+            &orig[100..200]
+        );
+    }
+
+    #[test]
+    fn test_stream_compress_random_perm() {
+        for cmode in [CompressionMode::Fast, CompressionMode::Slow] {
+            for secondary in [SecondaryCodec::None, SecondaryCodec::Lz4, SecondaryCodec::Deflate] {
+                let mut perm = random_lehmer(500);
+                lehmer_to_perm(&mut perm);
+                let orig = perm.clone();
+
+                let mut buf = Vec::new();
+                compress_permutation_to(cmode, secondary, perm, &mut buf).unwrap();
+
+                let mut recovered = Vec::new();
+                decompress_permutation_from(cmode, &mut &buf[..], |block| {
+                    recovered.extend_from_slice(block);
+                    Ok(())
+                })
+                .unwrap();
+
+                assert_eq!(recovered, orig);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_compress_matches_buffered_compress() {
+        let mut perm = random_lehmer(500);
+        lehmer_to_perm(&mut perm);
+        let orig = perm.clone();
+
+        let mut buf = Vec::new();
+        compress_permutation_to(CompressionMode::Fast, SecondaryCodec::None, perm.clone(), &mut buf)
+            .unwrap();
+
+        let buffered = compress_permutation(CompressionMode::Fast, perm);
+        let mut recovered_stream = Vec::new();
+        decompress_permutation_from(CompressionMode::Fast, &mut &buf[..], |block| {
+            recovered_stream.extend_from_slice(block);
+            Ok(())
+        })
+        .unwrap();
+        let recovered_buffered = decompress_permutation(CompressionMode::Fast, &buffered);
+
+        assert_eq!(recovered_stream, orig);
+        assert_eq!(recovered_buffered, orig);
+    }
+
+    #[test]
+    fn test_stream_decompress_fast_never_buffers_whole_permutation() {
+        let mut perm = random_lehmer(500);
+        lehmer_to_perm(&mut perm);
+        let orig = perm.clone();
+
+        let mut buf = Vec::new();
+        compress_permutation_to(CompressionMode::Fast, SecondaryCodec::None, perm, &mut buf).unwrap();
+
+        let block_len = Backend::detect().block_len();
+        let mut max_block_size = 0;
+        let mut recovered = Vec::new();
+        decompress_permutation_from(CompressionMode::Fast, &mut &buf[..], |block| {
+            max_block_size = usize::max(max_block_size, block.len());
+            recovered.extend_from_slice(block);
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(max_block_size <= block_len);
+        assert_eq!(recovered, orig);
+    }
 }
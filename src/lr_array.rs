@@ -1,12 +1,113 @@
 // Copyright 2021 Ryan Marcus, see COPYING
-use bitvec::prelude::BitVec;
+
+const WORD_BITS: usize = 64;
+const SUPERBLOCK_WORDS: usize = 32;
+const SUPERBLOCK_BITS: usize = SUPERBLOCK_WORDS * WORD_BITS;
+
+// Adds `delta` to the (0-indexed) superblock `idx` of a 1-indexed Fenwick tree.
+fn fenwick_add(tree: &mut [u32], idx: usize, delta: i64) {
+    let mut i = idx + 1;
+    while i < tree.len() {
+        tree[i] = (tree[i] as i64 + delta) as u32;
+        i += i & i.wrapping_neg();
+    }
+}
+
+// Sum of the values of superblocks `[0, idx)` (`idx` exclusive).
+fn fenwick_prefix_sum(tree: &[u32], idx: usize) -> usize {
+    let mut i = idx;
+    let mut sum = 0;
+    while i > 0 {
+        sum += tree[i] as usize;
+        i -= i & i.wrapping_neg();
+    }
+
+    return sum;
+}
+
+// Finds the (0-indexed) superblock containing the `k`-th (0-indexed) element counted by
+// `tree`, assuming every entry is non-negative and `k` is within the total sum. This is the
+// standard Fenwick-tree "find" query: walk decreasing powers of two, the same way a binary
+// search over the implicit tree would, in O(log(tree.len())).
+fn fenwick_find(tree: &[u32], k: usize) -> usize {
+    let n = tree.len() - 1;
+    let mut pos = 0;
+    let mut remaining = k;
+    let mut step = n.next_power_of_two();
+    if step > n {
+        step >>= 1;
+    }
+
+    while step > 0 {
+        let next = pos + step;
+        if next <= n && (tree[next] as usize) <= remaining {
+            pos = next;
+            remaining -= tree[next] as usize;
+        }
+        step >>= 1;
+    }
+
+    return pos;
+}
+
+// Finds the position (0..64) of the `k`-th (0-indexed) set bit in `x`. Computes each byte's
+// popcount via the standard SWAR trick, then turns those into running per-byte totals with
+// a single multiply by a word with a 1 in every byte (each output byte accumulates the sum of every
+// byte at or below its position, since a popcount can never exceed 8 and 8 such sums can
+// never overflow a byte). From there, a short scan picks out the target byte and then the
+// target bit.
+fn select_one_in_word(x: u64, k: u32) -> u32 {
+    debug_assert!(k < x.count_ones());
+
+    let mut s = x - ((x >> 1) & 0x5555555555555555);
+    s = (s & 0x3333333333333333) + ((s >> 2) & 0x3333333333333333);
+    s = (s + (s >> 4)) & 0x0F0F0F0F0F0F0F0F;
+    let byte_sums = s.wrapping_mul(0x0101010101010101);
+
+    let mut byte_idx = 0;
+    while byte_idx < 7 {
+        let cumulative = (byte_sums >> (byte_idx * 8)) & 0xFF;
+        if cumulative as u32 > k {
+            break;
+        }
+        byte_idx += 1;
+    }
+
+    let mut remaining = k;
+    if byte_idx > 0 {
+        remaining -= ((byte_sums >> ((byte_idx - 1) * 8)) & 0xFF) as u32;
+    }
+
+    let byte_val = ((x >> (byte_idx * 8)) & 0xFF) as u8;
+    let mut seen = 0;
+    for bit in 0..8 {
+        if (byte_val >> bit) & 1 == 1 {
+            if seen == remaining {
+                return (byte_idx * 8 + bit) as u32;
+            }
+            seen += 1;
+        }
+    }
+
+    unreachable!("k-th set bit not found within word");
+}
+
+fn select_zero_in_word(x: u64, k: u32) -> u32 {
+    return select_one_in_word(!x, k);
+}
 
 /// A specialized fixed-size bit vector with the following operations:
 /// 1) find (and optionally set) the kth unset bit in O(log(n))
 /// 2) set the nth bit in O(log(n))
 /// 3) count the number of unset bits before index k in O(log(n))
 ///
-/// Loosely based on the LRArray from Jörg Arndt's FXT book.
+/// Backed by a two-level succinct rank/select structure rather than the `2 * size`-word
+/// tree this used to be: raw bits live in 64-bit words, grouped into superblocks of
+/// `SUPERBLOCK_WORDS` words, and a Fenwick tree over the per-superblock free-bit counts
+/// gives a prefix sum (for `unset_before`) or a "find the k-th free bit" query (for
+/// `set_kth_unset_bit`) over the superblocks, finished off with a linear scan of the O(1)
+/// words inside that superblock and a broadword select within the last word. This cuts the
+/// index overhead from ~2 words per bit down to one `u32` per 2048 bits.
 pub struct LRArray {
     /// total number of bits
     total_bits: usize,
@@ -14,27 +115,29 @@ pub struct LRArray {
     /// total set bits
     total_set_bits: usize,
 
-    /// raw bit values
-    vals: BitVec,
+    /// raw bit values, 64 per word
+    words: Vec<u64>,
 
-    /// laid out as a tree, the number of bits set of each node's children
-    f: Vec<usize>,
+    /// 1-indexed Fenwick tree over the number of unset bits in each superblock
+    free_bits: Vec<u32>,
 }
 
 impl LRArray {
     pub fn new(size: usize) -> LRArray {
-        let mut vals = BitVec::with_capacity(size);
-        for _ in 0..size {
-            vals.push(false);
-        }
+        let num_words = size.div_ceil(WORD_BITS);
+        let num_superblocks = size.div_ceil(SUPERBLOCK_BITS);
 
-        let f = vec![0; size * 2];
+        let mut free_bits = vec![0_u32; num_superblocks + 1];
+        for sb in 0..num_superblocks {
+            let valid = usize::min(SUPERBLOCK_BITS, size - sb * SUPERBLOCK_BITS);
+            fenwick_add(&mut free_bits, sb, valid as i64);
+        }
 
         return LRArray {
-            vals,
-            f,
             total_bits: size,
             total_set_bits: 0,
+            words: vec![0; num_words],
+            free_bits,
         };
     }
 
@@ -52,7 +155,24 @@ impl LRArray {
 
     #[cfg(test)]
     pub fn get_bit(&self, n: usize) -> bool {
-        return self.vals[n];
+        return (self.words[n / WORD_BITS] >> (n % WORD_BITS)) & 1 == 1;
+    }
+
+    // The content of word `word_idx`, with any bits past `total_bits` forced to 1 (i.e.
+    // treated as set) so that the last, possibly partial, word never contributes phantom
+    // free bits to a count or select query.
+    fn effective_word(&self, word_idx: usize) -> u64 {
+        let word = self.words[word_idx];
+        if word_idx != self.words.len() - 1 {
+            return word;
+        }
+
+        let valid = self.total_bits - word_idx * WORD_BITS;
+        if valid >= WORD_BITS {
+            return word;
+        }
+
+        return word | (!0_u64 << valid);
     }
 
     pub fn unset_before(&self, n: usize) -> usize {
@@ -60,76 +180,49 @@ impl LRArray {
             return self.unset_bits();
         }
 
-        let mut curr_start = 0;
-        let mut curr_stop = self.total_bits;
-        let mut f_idx = 0;
-        let mut num_bits_before = 0;
-        debug_assert_eq!(self.f[0], self.set_bits());
-
-        while curr_stop - curr_start > 2 {
-            let left_child_idx = f_idx * 2 + 1;
-            let right_child_idx = f_idx * 2 + 2;
-
-            let child_range_size = (curr_stop - curr_start) / 2;
-            let free_bits_left = child_range_size - self.f[left_child_idx];
-            let midpoint = curr_start + child_range_size;
-
-            if n < midpoint {
-                // go left
-                curr_stop -= child_range_size;
-                f_idx = left_child_idx;
-            } else {
-                // go right
-                curr_start += child_range_size;
-                f_idx = right_child_idx;
-                num_bits_before += free_bits_left;
-            }
+        let superblock_idx = n / SUPERBLOCK_BITS;
+        let mut free_before = fenwick_prefix_sum(&self.free_bits, superblock_idx);
+
+        let superblock_start = superblock_idx * SUPERBLOCK_BITS;
+        let first_word = superblock_start / WORD_BITS;
+        let target_word = n / WORD_BITS;
+
+        for word in &self.words[first_word..target_word] {
+            free_before += word.count_zeros() as usize;
         }
 
-        // the binary search above narrows it down to a range of size 2
-        if n > curr_start && !self.vals[n - 1] {
-            num_bits_before += 1;
+        let bit_in_word = n % WORD_BITS;
+        if bit_in_word > 0 {
+            let mask = (1_u64 << bit_in_word) - 1;
+            free_before += (!self.words[target_word] & mask).count_ones() as usize;
         }
 
-        return num_bits_before;
+        return free_before;
+    }
+
+    // Recomputes the set-bit count directly from the raw words, so callers can sanity-check
+    // `total_set_bits` against ground truth in debug builds.
+    #[cfg(debug_assertions)]
+    fn count_set_bits(&self) -> usize {
+        return self.words.iter().map(|w| w.count_ones() as usize).sum();
     }
 
     pub fn set_nth_bit(&mut self, n: usize) -> bool {
-        if self.vals[n] {
-            return true;
-        }
+        let word_idx = n / WORD_BITS;
+        let mask = 1_u64 << (n % WORD_BITS);
 
-        let mut curr_start = 0;
-        let mut curr_stop = self.total_bits;
-        let mut f_idx = 0;
-        debug_assert_eq!(self.f[0], self.set_bits());
-        self.f[0] += 1;
-
-        while curr_stop - curr_start > 2 {
-            let left_child_idx = f_idx * 2 + 1;
-            let right_child_idx = f_idx * 2 + 2;
-
-            let child_range_size = (curr_stop - curr_start) / 2;
-            let midpoint = curr_start + child_range_size;
-
-            if n < midpoint {
-                // go left
-                curr_stop -= child_range_size;
-                f_idx = left_child_idx;
-            } else {
-                // go right
-                curr_start += child_range_size;
-                f_idx = right_child_idx;
-            }
-            self.f[f_idx] += 1;
+        if self.words[word_idx] & mask != 0 {
+            return true;
         }
 
-        self.vals.set(n, true);
+        self.words[word_idx] |= mask;
         self.total_set_bits += 1;
+        fenwick_add(&mut self.free_bits, n / SUPERBLOCK_BITS, -1);
+        debug_assert_eq!(self.set_bits(), self.count_set_bits());
         return false;
     }
 
-    pub fn set_kth_unset_bit(&mut self, mut k: usize) -> usize {
+    pub fn set_kth_unset_bit(&mut self, k: usize) -> usize {
         if k >= self.unset_bits() {
             panic!(
                 "Trying to set {}th free bit, but only {} free bits left",
@@ -138,48 +231,34 @@ impl LRArray {
             );
         }
 
-        let mut curr_start = 0;
-        let mut curr_stop = self.total_bits;
-        let mut f_idx = 0;
-        debug_assert_eq!(self.f[0], self.set_bits());
-        self.f[0] += 1;
-
-        while curr_stop - curr_start > 2 {
-            let left_child_idx = f_idx * 2 + 1;
-            let right_child_idx = f_idx * 2 + 2;
-
-            let child_range_size = (curr_stop - curr_start) / 2;
-
-            #[cfg(test)]
-            let free_bits_left = child_range_size - self.f[left_child_idx];
-            
-            #[cfg(not(test))]
-            let free_bits_left = unsafe { child_range_size - self.f.get_unchecked(left_child_idx) };
-
-            if free_bits_left > k {
-                // go left
-                curr_stop -= child_range_size;
-                f_idx = left_child_idx;
-            } else {
-                // go right
-                curr_start += child_range_size;
-                f_idx = right_child_idx;
-                k -= free_bits_left;
+        let superblock_idx = fenwick_find(&self.free_bits, k);
+        let free_before_superblock = fenwick_prefix_sum(&self.free_bits, superblock_idx);
+        let mut remaining = k - free_before_superblock;
+
+        let first_word = (superblock_idx * SUPERBLOCK_BITS) / WORD_BITS;
+        let last_word = usize::min(first_word + SUPERBLOCK_WORDS, self.words.len());
+
+        let mut target_word = last_word - 1;
+        for word_idx in first_word..last_word {
+            let free_in_word = self.effective_word(word_idx).count_zeros() as usize;
+            if free_in_word > remaining {
+                target_word = word_idx;
+                break;
             }
-            self.f[f_idx] += 1;
+            remaining -= free_in_word;
         }
 
-        // the binary search above narrows it down to a range of size 2
-        debug_assert!(k < 2);
-        let idx = if k == 1 || self.vals[curr_start] {
-            curr_start + 1
-        } else {
-            curr_start
-        };
+        let bit_idx = select_zero_in_word(self.effective_word(target_word), remaining as u32);
+        let idx = target_word * WORD_BITS + bit_idx as usize;
 
-        debug_assert!(!self.vals[idx]);
-        self.vals.set(idx, true);
+        debug_assert!(idx < self.total_bits);
+        debug_assert_eq!(self.words[target_word] & (1_u64 << bit_idx), 0);
+
+        self.words[target_word] |= 1_u64 << bit_idx;
         self.total_set_bits += 1;
+        fenwick_add(&mut self.free_bits, superblock_idx, -1);
+        debug_assert_eq!(self.set_bits(), self.count_set_bits());
+
         return idx;
     }
 }
@@ -240,4 +319,41 @@ mod lr_tests {
         assert_eq!(array.set_kth_unset_bit(3), 3);
         assert_eq!(array.set_kth_unset_bit(3), 4);
     }
+
+    #[test]
+    fn test_select_one_in_word() {
+        let x: u64 = 0b1011_0100;
+        assert_eq!(select_one_in_word(x, 0), 2);
+        assert_eq!(select_one_in_word(x, 1), 4);
+        assert_eq!(select_one_in_word(x, 2), 5);
+        assert_eq!(select_one_in_word(x, 3), 7);
+    }
+
+    fn brute_force_unset_before(array: &LRArray, n: usize) -> usize {
+        return (0..n).filter(|&i| !array.get_bit(i)).count();
+    }
+
+    #[test]
+    fn test_spans_many_superblocks() {
+        // SUPERBLOCK_BITS is 2048, so this exercises several superblocks plus a partial
+        // last superblock and a partial last word.
+        let size = SUPERBLOCK_BITS * 3 + 77;
+        let mut array = LRArray::new(size);
+        assert_eq!(array.unset_bits(), size);
+
+        let mut set_so_far = Vec::new();
+        for k in 0..500 {
+            let free_before = array.unset_bits();
+            let idx = array.set_kth_unset_bit((k * 677) % free_before);
+            assert!(!set_so_far.contains(&idx));
+            set_so_far.push(idx);
+
+            for &n in &[0, idx, idx + 1, size / 2, size] {
+                assert_eq!(array.unset_before(n), brute_force_unset_before(&array, n));
+            }
+        }
+
+        assert_eq!(array.unset_bits(), size - 500);
+        assert_eq!(set_so_far.len(), 500);
+    }
 }
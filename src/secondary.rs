@@ -0,0 +1,137 @@
+// Copyright 2021 Ryan Marcus, see COPYING
+use std::io::{self, Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+
+/// General-purpose byte codec layered over the already-bitpacked permutation payload.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SecondaryCodec {
+    None,
+    Lz4,
+    Deflate,
+}
+
+impl SecondaryCodec {
+    pub fn from_tag(tag: u8) -> SecondaryCodec {
+        return match tag {
+            0 => SecondaryCodec::None,
+            1 => SecondaryCodec::Lz4,
+            2 => SecondaryCodec::Deflate,
+            _ => panic!("unknown secondary codec tag: {}", tag),
+        };
+    }
+
+    pub fn tag(self) -> u8 {
+        return match self {
+            SecondaryCodec::None => 0,
+            SecondaryCodec::Lz4 => 1,
+            SecondaryCodec::Deflate => 2,
+        };
+    }
+}
+
+/// Streams bytes through the chosen codec instead of buffering them first; `finish()`
+/// flushes and hands the inner writer back.
+pub enum SecondaryWriter<W: Write> {
+    None(W),
+    Lz4(FrameEncoder<W>),
+    Deflate(DeflateEncoder<W>),
+}
+
+impl<W: Write> SecondaryWriter<W> {
+    pub fn new(codec: SecondaryCodec, inner: W) -> SecondaryWriter<W> {
+        return match codec {
+            SecondaryCodec::None => SecondaryWriter::None(inner),
+            SecondaryCodec::Lz4 => SecondaryWriter::Lz4(FrameEncoder::new(inner)),
+            SecondaryCodec::Deflate => {
+                SecondaryWriter::Deflate(DeflateEncoder::new(inner, Compression::default()))
+            }
+        };
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        return match self {
+            SecondaryWriter::None(w) => Ok(w),
+            SecondaryWriter::Lz4(e) => e.finish().map_err(io::Error::from),
+            SecondaryWriter::Deflate(e) => e.finish(),
+        };
+    }
+}
+
+impl<W: Write> Write for SecondaryWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return match self {
+            SecondaryWriter::None(w) => w.write(buf),
+            SecondaryWriter::Lz4(e) => e.write(buf),
+            SecondaryWriter::Deflate(e) => e.write(buf),
+        };
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return match self {
+            SecondaryWriter::None(w) => w.flush(),
+            SecondaryWriter::Lz4(e) => e.flush(),
+            SecondaryWriter::Deflate(e) => e.flush(),
+        };
+    }
+}
+
+/// Read-side counterpart of `SecondaryWriter`.
+pub enum SecondaryReader<R: Read> {
+    None(R),
+    Lz4(FrameDecoder<R>),
+    Deflate(DeflateDecoder<R>),
+}
+
+impl<R: Read> SecondaryReader<R> {
+    pub fn new(codec: SecondaryCodec, inner: R) -> SecondaryReader<R> {
+        return match codec {
+            SecondaryCodec::None => SecondaryReader::None(inner),
+            SecondaryCodec::Lz4 => SecondaryReader::Lz4(FrameDecoder::new(inner)),
+            SecondaryCodec::Deflate => SecondaryReader::Deflate(DeflateDecoder::new(inner)),
+        };
+    }
+}
+
+impl<R: Read> Read for SecondaryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return match self {
+            SecondaryReader::None(r) => r.read(buf),
+            SecondaryReader::Lz4(d) => d.read(buf),
+            SecondaryReader::Deflate(d) => d.read(buf),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_round_trip() {
+        for codec in [SecondaryCodec::None, SecondaryCodec::Lz4, SecondaryCodec::Deflate] {
+            assert_eq!(SecondaryCodec::from_tag(codec.tag()), codec);
+        }
+    }
+
+    #[test]
+    fn test_every_codec_round_trips() {
+        let data: Vec<u8> = (0..4096_u32).map(|i| (i % 17) as u8).collect();
+
+        for codec in [SecondaryCodec::None, SecondaryCodec::Lz4, SecondaryCodec::Deflate] {
+            let mut buf = Vec::new();
+            let mut writer = SecondaryWriter::new(codec, &mut buf);
+            writer.write_all(&data).unwrap();
+            writer.finish().unwrap();
+
+            let mut reader = SecondaryReader::new(codec, &buf[..]);
+            let mut recovered = Vec::new();
+            reader.read_to_end(&mut recovered).unwrap();
+
+            assert_eq!(recovered, data);
+        }
+    }
+}